@@ -1,24 +1,39 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
+use axum::{routing::get, Json, Router};
 use dotenv::dotenv;
 use isahc::{AsyncBody, AsyncReadResponseExt, HttpClient, Request, Response};
 use itertools::Itertools;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 use tokio_cron_scheduler::{Job, JobScheduler};
 use tracing::{debug, error, info, instrument, Level};
 
+type SharedStatus = Arc<Mutex<RunStatus>>;
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     tracing_subscriber::fmt().with_max_level(Level::INFO).init();
 
     dotenv().ok();
 
+    let status: SharedStatus = Arc::new(Mutex::new(RunStatus::default()));
+
+    tokio::spawn(serve_status(status.clone()));
+
     let mut sched = JobScheduler::new().await?;
 
+    let job_status = status.clone();
     sched
-        .add(Job::new_async("0 0 * * * *", |_, _| {
+        .add(Job::new_async("0 0 * * * *", move |_, _| {
+            let status = job_status.clone();
             Box::pin(async move {
-                do_work().await.unwrap();
+                if let Err(e) = do_work(&status).await {
+                    error!("do_work failed: {}", e);
+                }
             })
         })?)
         .await?;
@@ -36,86 +51,222 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-async fn do_work() -> Result<(), Box<dyn std::error::Error>> {
+/// Serves `/status` (the most recent `do_work` outcome) and `/healthz` so the
+/// scheduled reversal can be observed without tailing logs.
+async fn serve_status(status: SharedStatus) {
+    let bind_addr =
+        std::env::var("STATUS_BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
+
+    let app = Router::new()
+        .route(
+            "/status",
+            get({
+                let status = status.clone();
+                move || async move { Json(status.lock().await.clone()) }
+            }),
+        )
+        .route("/healthz", get(|| async { "ok" }));
+
+    let listener = match tokio::net::TcpListener::bind(&bind_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind status server to {}: {}", bind_addr, e);
+            return;
+        }
+    };
+
+    info!("Status server listening on {}", bind_addr);
+
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Status server failed: {}", e);
+    }
+}
+
+async fn do_work(status: &SharedStatus) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let client = HttpClient::new()?;
 
-    let from = std::env::var("FROM")?;
-    let to = std::env::var("TO")?;
+    let mappings = match load_mappings() {
+        Ok(mappings) => mappings,
+        Err(e) => {
+            error!("Failed to load mappings: {}", e);
+            record_failure(status, e.as_ref()).await;
+            return Err(e);
+        }
+    };
 
     let token = match get_token(&client).await {
         Ok(token) => token,
         Err(e) => {
             error!("Failed to acquire token: {}", e);
+            record_failure(status, e.as_ref()).await;
             return Err(e);
         }
     };
 
     info!("Token acquired, {}, {}", token.access_token, token.scope);
 
-    match reset_reversed(&client, &token.access_token, &to).await {
-        Ok(_) => info!("Reversed reset successfully"),
-        Err(e) => {
-            error!("Failed to reset reversed: {}", e);
-            return Err(e);
+    let mut songs_fetched = 0;
+    let mut songs_added = 0;
+    let mut last_error = None;
+
+    for mapping in &mappings {
+        match reverse_mapping(&client, &token.access_token, mapping).await {
+            Ok((fetched, added)) => {
+                info!(
+                    "Reversed {} -> {} ({} songs)",
+                    mapping.from, mapping.to, added
+                );
+                songs_fetched += fetched;
+                songs_added += added;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to reverse {} -> {}: {}",
+                    mapping.from, mapping.to, e
+                );
+                last_error = Some(e);
+            }
         }
     }
 
-    let songs = match get_songs(&client, &token.access_token, &from).await {
-        Ok(songs) => songs,
-        Err(e) => {
-            error!("Failed to get songs: {}", e);
-            return Err(e);
+    match last_error {
+        Some(e) => {
+            record_partial_failure(status, songs_fetched, songs_added, e.as_ref()).await;
+            Err(e)
         }
-    };
+        None => {
+            record_success(status, songs_fetched, songs_added).await;
+            Ok(())
+        }
+    }
+}
+
+/// Resets `mapping.to`, fetches `mapping.from` and re-adds it in reversed order.
+/// Returns the number of songs fetched and the number added.
+async fn reverse_mapping(
+    client: &HttpClient,
+    token: &str,
+    mapping: &Mapping,
+) -> Result<(usize, usize), Box<dyn std::error::Error + Send + Sync>> {
+    let from = PlaylistId::new(mapping.from.as_str())?;
+    let to = PlaylistId::new(mapping.to.as_str())?;
+
+    reset_reversed(client, token, &to).await?;
 
-    info!("Got {} songs", songs.len());
+    let songs = get_songs(client, token, &from).await?;
+    let songs_fetched = songs.len();
 
-    let mut iter = songs
+    // Local files report a `spotify:local:...` uri that doesn't fit the regular
+    // track-id shape, so they're filtered out before ever being parsed as a `TrackUri`.
+    let uris: Vec<TrackUri<'static>> = songs
         .into_iter()
         .filter(|song| !song.is_local)
         .sorted_by(|first, second| Ord::cmp(&first.added_at, &second.added_at))
-        .map(|song| song.track.uri)
-        .rev();
-
-    while let Some(first) = iter.next() {
-        let next_next = iter.next();
-        let vec = match next_next {
-            Some(next) => vec![first, next],
-            None => vec![first],
-        };
-
-        match add_songs(&client, &token.access_token, &vec[..], &to).await {
-            Ok(_) => debug!("Added songs {:?}", vec),
-            Err(e) => {
-                error!("Failed to add songs {:?}: {}", vec, e);
-                return Err(e);
-            }
-        }
+        .map(|song| TrackUri::new(song.track.uri))
+        .collect::<Result<_, _>>()?;
+
+    // Spotify accepts up to 100 URIs per add-tracks call. Chunk the ascending-sorted
+    // URIs first (`chunks` needs a forward iterator), then emit the chunks from last
+    // to first and reverse each one's contents, so the playlist ends up fully reversed.
+    let chunks: Vec<Vec<TrackUri<'static>>> = uris
+        .into_iter()
+        .chunks(100)
+        .into_iter()
+        .map(|chunk| chunk.collect())
+        .collect();
+
+    let mut songs_added = 0;
+
+    for mut chunk in chunks.into_iter().rev() {
+        chunk.reverse();
 
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        add_songs(client, token, &chunk, &to).await?;
+        songs_added += chunk.len();
+
+        debug!("Added songs {:?}", chunk);
     }
 
-    Ok(())
+    Ok((songs_fetched, songs_added))
+}
+
+/// Loads the `from`/`to` playlist mappings to reverse, from a JSON config file at
+/// `MAPPINGS_CONFIG_PATH`, or else `mappings.json` if present. Falls back to the
+/// single `FROM`/`TO` env var pair only when no config file is present at all, so
+/// existing single-playlist deployments keep working unchanged. An explicitly set
+/// `MAPPINGS_CONFIG_PATH` that can't be read is a hard error, not a fallback trigger.
+fn load_mappings() -> Result<Vec<Mapping>, Box<dyn std::error::Error + Send + Sync>> {
+    // An explicitly configured path that can't be read is a misconfiguration, not
+    // "no config file present" — only fall back to FROM/TO when the var is unset.
+    if let Ok(path) = std::env::var("MAPPINGS_CONFIG_PATH") {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read MAPPINGS_CONFIG_PATH {path}: {e}"))?;
+
+        return Ok(serde_json::from_str(&contents)?);
+    }
+
+    if let Ok(contents) = std::fs::read_to_string("mappings.json") {
+        return Ok(serde_json::from_str(&contents)?);
+    }
+
+    Ok(vec![Mapping {
+        from: std::env::var("FROM")?,
+        to: std::env::var("TO")?,
+    }])
+}
+
+async fn record_success(status: &SharedStatus, songs_fetched: usize, songs_added: usize) {
+    let mut status = status.lock().await;
+    status.last_success_at = Some(now_unix());
+    status.songs_fetched = songs_fetched;
+    status.songs_added = songs_added;
+    status.last_error = None;
+}
+
+async fn record_failure(status: &SharedStatus, error: &(dyn std::error::Error + Send + Sync)) {
+    status.lock().await.last_error = Some(error.to_string());
+}
+
+/// Records a run in which at least one mapping failed. The counts cover the mappings
+/// that did succeed (zero if none did), so `/status` reflects them instead of stale
+/// counts from whatever run last fully succeeded; `last_error` holds the error of the
+/// last mapping that failed.
+async fn record_partial_failure(
+    status: &SharedStatus,
+    songs_fetched: usize,
+    songs_added: usize,
+    error: &(dyn std::error::Error + Send + Sync),
+) {
+    let mut status = status.lock().await;
+    status.songs_fetched = songs_fetched;
+    status.songs_added = songs_added;
+    status.last_error = Some(error.to_string());
 }
 
 #[instrument(skip(token))]
 async fn add_songs(
     client: &HttpClient,
     token: &str,
-    uris: &[String],
-    playlist_id: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    uris: &[TrackUri<'_>],
+    playlist_id: &PlaylistId<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     debug!("Adding songs {:?}", uris);
 
-    let request = Request::post(format!(
-        "https://api.spotify.com/v1/playlists/{playlist_id}/tracks"
-    ))
-    .header("Authorization", format!("Bearer {}", token))
-    .body(serde_json::to_string(&serde_json::json!({
+    let uris: Vec<String> = uris.iter().map(ToString::to_string).collect();
+
+    let body = serde_json::to_string(&serde_json::json!({
         "uris": uris
-    }))?)?;
+    }))?;
+
+    let response = send_with_retry(client, MAX_RETRIES, || {
+        Request::post(format!(
+            "https://api.spotify.com/v1/playlists/{playlist_id}/tracks"
+        ))
+        .header("Authorization", format!("Bearer {}", token))
+        .body(body.clone())
+    })
+    .await?;
 
-    match client.send_async(request).await?.ok() {
+    match response.ok() {
         Ok(_) => Ok(()),
         Err(mut e) => Err(format!("Failed to add song: {}", e.text().await?).into()),
     }
@@ -125,8 +276,8 @@ async fn add_songs(
 async fn get_songs(
     client: &HttpClient,
     token: &str,
-    playlist_id: &str,
-) -> Result<Vec<Song>, Box<dyn std::error::Error>> {
+    playlist_id: &PlaylistId<'_>,
+) -> Result<Vec<Song>, Box<dyn std::error::Error + Send + Sync>> {
     info!("Getting songs");
 
     let mut vec = vec![];
@@ -134,13 +285,16 @@ async fn get_songs(
     let mut offset = 0;
 
     while should_cont {
-        let request = Request::get(format!(
-            "https://api.spotify.com/v1/playlists/{playlist_id}/tracks?offset={offset}&limit=50",
-        ))
-        .header("Authorization", format!("Bearer {}", token))
-        .body(())?;
+        let response = send_with_retry(client, MAX_RETRIES, || {
+            Request::get(format!(
+                "https://api.spotify.com/v1/playlists/{playlist_id}/tracks?offset={offset}&limit=50",
+            ))
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+        })
+        .await?;
 
-        match client.send_async(request).await?.ok() {
+        match response.ok() {
             Ok(mut response) => {
                 info!("Got songs at offset {offset}");
 
@@ -163,31 +317,47 @@ async fn get_songs(
 async fn reset_reversed(
     client: &HttpClient,
     token: &str,
-    playlist_id: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    playlist_id: &PlaylistId<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("Resetting reversed");
 
-    let request = Request::put(format!(
-        "https://api.spotify.com/v1/playlists/{playlist_id}/tracks"
-    ))
-    .header("Content-Type", "application/json")
-    .header("Authorization", format!("Bearer {}", token))
-    .body(serde_json::to_string(&serde_json::json!({
+    let body = serde_json::to_string(&serde_json::json!({
         "uris": []
-    }))?)?;
+    }))?;
 
-    info!("Sending request to reset reversed, {request:?}");
+    let response = send_with_retry(client, MAX_RETRIES, || {
+        Request::put(format!(
+            "https://api.spotify.com/v1/playlists/{playlist_id}/tracks"
+        ))
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(body.clone())
+    })
+    .await?;
 
-    match client.send_async(request).await?.ok() {
+    match response.ok() {
         Ok(_) => Ok(()),
         Err(mut e) => Err(format!("Failed to reset reversed: {}", e.text().await?).into()),
     }
 }
 
 #[instrument]
-async fn get_token(client: &HttpClient) -> Result<AccessToken, Box<dyn std::error::Error>> {
+async fn get_token(
+    client: &HttpClient,
+) -> Result<AccessToken, Box<dyn std::error::Error + Send + Sync>> {
     use base64::{engine::general_purpose, Engine as _};
 
+    if let Some(cached) = load_cached_token() {
+        if cached.expires_at > now_unix() + TOKEN_REFRESH_MARGIN.as_secs() {
+            info!("Using cached access token");
+            return Ok(AccessToken {
+                access_token: cached.access_token,
+                scope: cached.scope,
+                expires_in: cached.expires_at.saturating_sub(now_unix()),
+            });
+        }
+    }
+
     let refresh_token = std::env::var("REFRESH_TOKEN")?;
 
     let encoded = general_purpose::STANDARD.encode(
@@ -200,19 +370,81 @@ async fn get_token(client: &HttpClient) -> Result<AccessToken, Box<dyn std::erro
     );
 
     // TODO client id and secret
-    let request = Request::post("https://accounts.spotify.com/api/token")
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .header("Authorization", format!("Basic {encoded}"))
-        .body(format!(
+    let body = format!(
         "grant_type=refresh_token&refresh_token={refresh_token}&scope=playlist-read-private%20playlist-modify-private%20playlist-modify-public%20user-library-read%20user-library-modify"
-    ))?;
+    );
 
     info!("Sending request to acquire token");
 
-    match client.send_async(request).await?.ok() {
-        Ok(mut response) => Ok(response.json::<AccessToken>().await?),
-        Err(mut e) => Err(format!("Failed to acquire token: {}", e.text().await?).into()),
+    let response = send_with_retry(client, MAX_RETRIES, || {
+        Request::post("https://accounts.spotify.com/api/token")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Authorization", format!("Basic {encoded}"))
+            .body(body.clone())
+    })
+    .await?;
+
+    let token = match response.ok() {
+        Ok(mut response) => response.json::<AccessToken>().await?,
+        Err(mut e) => return Err(format!("Failed to acquire token: {}", e.text().await?).into()),
+    };
+
+    if let Err(e) = store_cached_token(&token) {
+        error!("Failed to cache access token: {}", e);
     }
+
+    Ok(token)
+}
+
+/// Path to the on-disk token cache, configurable via `TOKEN_CACHE_PATH` (defaults to
+/// `token_cache.json` in the working directory).
+fn token_cache_path() -> std::path::PathBuf {
+    std::env::var("TOKEN_CACHE_PATH")
+        .unwrap_or_else(|_| "token_cache.json".to_string())
+        .into()
+}
+
+fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn load_cached_token() -> Option<CachedToken> {
+    let contents = std::fs::read_to_string(token_cache_path()).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn store_cached_token(token: &AccessToken) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let cached = CachedToken {
+        access_token: token.access_token.clone(),
+        scope: token.scope.clone(),
+        expires_at: now_unix() + token.expires_in,
+    };
+
+    let path = token_cache_path();
+
+    std::fs::write(&path, serde_json::to_string(&cached)?)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Clone)]
+struct Mapping {
+    from: String,
+    to: String,
+}
+
+#[derive(Clone, Default, Serialize)]
+struct RunStatus {
+    last_success_at: Option<u64>,
+    songs_fetched: usize,
+    songs_added: usize,
+    last_error: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -230,13 +462,148 @@ struct Song {
 
 #[derive(Deserialize, Debug)]
 struct Track {
+    // Local files report a non-standard `spotify:local:...` uri, so this is kept as a
+    // plain string and only parsed into a `TrackUri` for the non-local tracks we send on.
     uri: String,
 }
 
+/// A Spotify playlist id, e.g. `37i9dQZF1DXcBWIGoYBM5M` or `spotify:playlist:37i9dQZF1DXcBWIGoYBM5M`.
+///
+/// Renders (via `Display`) as the bare id, the form the playlists API expects in its
+/// URL path. Backed by a `Cow` so ids borrowed from config stay allocation-free while
+/// ids parsed out of API responses can still be owned.
+#[derive(Debug, Clone)]
+struct PlaylistId<'a>(Cow<'a, str>);
+
+impl<'a> PlaylistId<'a> {
+    fn new(id: impl Into<Cow<'a, str>>) -> Result<Self, InvalidSpotifyId> {
+        Ok(Self(parse_spotify_id(id.into(), "playlist")?))
+    }
+}
+
+impl fmt::Display for PlaylistId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A Spotify track URI, e.g. `4cOdK2wGLETKBW3PvgPWqT` or `spotify:track:4cOdK2wGLETKBW3PvgPWqT`.
+///
+/// Renders (via `Display`) as the full `spotify:track:<id>` URI, the form the
+/// add-tracks API expects in its request body.
+#[derive(Debug, Clone)]
+struct TrackUri<'a>(Cow<'a, str>);
+
+impl<'a> TrackUri<'a> {
+    fn new(id: impl Into<Cow<'a, str>>) -> Result<Self, InvalidSpotifyId> {
+        Ok(Self(parse_spotify_id(id.into(), "track")?))
+    }
+}
+
+impl fmt::Display for TrackUri<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spotify:track:{}", self.0)
+    }
+}
+
+/// Strips the `spotify:{kind}:` prefix when present and validates the remaining id is
+/// non-empty and alphanumeric, rejecting malformed ids at the boundary instead of
+/// silently posting them to the API.
+fn parse_spotify_id<'a>(
+    id: Cow<'a, str>,
+    kind: &'static str,
+) -> Result<Cow<'a, str>, InvalidSpotifyId> {
+    let prefix = format!("spotify:{kind}:");
+
+    let bare = match id {
+        Cow::Borrowed(s) => Cow::Borrowed(s.strip_prefix(&prefix).unwrap_or(s)),
+        Cow::Owned(s) => match s.strip_prefix(&prefix) {
+            Some(stripped) => Cow::Owned(stripped.to_string()),
+            None => Cow::Owned(s),
+        },
+    };
+
+    if bare.is_empty() || !bare.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(InvalidSpotifyId {
+            kind,
+            value: bare.into_owned(),
+        });
+    }
+
+    Ok(bare)
+}
+
+#[derive(Debug)]
+struct InvalidSpotifyId {
+    kind: &'static str,
+    value: String,
+}
+
+impl fmt::Display for InvalidSpotifyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid spotify {} id: {:?}", self.kind, self.value)
+    }
+}
+
+impl std::error::Error for InvalidSpotifyId {}
+
 #[derive(Deserialize)]
 struct AccessToken {
     access_token: String,
     scope: String,
+    expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    access_token: String,
+    scope: String,
+    expires_at: u64,
+}
+
+const MAX_RETRIES: u32 = 5;
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Sends a request built by `build_request`, retrying on HTTP 429 responses.
+///
+/// `Request` is consumed by `send_async`, so `build_request` is called again on each
+/// attempt to produce a fresh request. The wait between attempts comes from the
+/// response's `Retry-After` header when present, falling back to `DEFAULT_RETRY_AFTER`.
+async fn send_with_retry<B, F>(
+    client: &HttpClient,
+    max_retries: u32,
+    mut build_request: F,
+) -> Result<Response<AsyncBody>, Box<dyn std::error::Error + Send + Sync>>
+where
+    B: Into<AsyncBody>,
+    F: FnMut() -> Result<Request<B>, isahc::http::Error>,
+{
+    let mut attempts = 0;
+
+    loop {
+        let response = client.send_async(build_request()?).await?;
+
+        if response.status().as_u16() != 429 || attempts >= max_retries {
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+
+        attempts += 1;
+        debug!(
+            "Rate limited (attempt {}/{}), retrying in {:?}",
+            attempts, max_retries, retry_after
+        );
+
+        tokio::time::sleep(retry_after).await;
+    }
 }
 
 trait OkExt: Sized {